@@ -6,6 +6,61 @@ use serde::{de, Deserialize};
 use serde_json::{Result, Value};
 use std::{error::Error, fs::File, path::Path, time::Duration};
 
+// How many records to ask the results service for per request.
+const PAGE_SIZE: u32 = 100;
+
+/// Fetches a single page of records starting at `offset`, asking for up to
+/// `PAGE_SIZE` records.
+fn fetch_page(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    offset: u32,
+) -> reqwest::Result<Results> {
+    client
+        .get(url)
+        .query(&[("offset", offset), ("limit", PAGE_SIZE)])
+        .send()?
+        .error_for_status()?
+        .json::<Results>()
+}
+
+/// Fetches every page of records from the live results service at `url`,
+/// concatenating them into a single `Vec` the same way the records of a
+/// single local `Results` blob would be laid out. A page that fails to load
+/// is retried once before the error is surfaced to the caller.
+fn fetch_all_records(url: &str, debug: bool) -> Result<Vec<Value>> {
+    let client = reqwest::blocking::Client::new();
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let page = fetch_page(&client, url, offset).or_else(|first_err| {
+            if debug {
+                println!(
+                    "Warning: page at offset {} failed ({}), retrying once",
+                    offset, first_err
+                );
+            }
+            fetch_page(&client, url, offset)
+        });
+        let mut page = page.map_err(|e| de::Error::custom(format!("couldn't fetch {}: {}", url, e)))?;
+
+        // `query_record_count` is the service's own account of how many
+        // records it put in this response; that's what we trust to advance
+        // the offset rather than assuming it lines up with `records.len()`.
+        let advance = page.query_record_count;
+        let total = page.total_record_count;
+        records.append(&mut page.records);
+
+        if advance == 0 || records.len() as u32 >= total {
+            break;
+        }
+        offset += advance;
+    }
+
+    Ok(records)
+}
+
 arg_enum! {
     #[derive(Debug, PartialEq, Clone)]
     enum Course {
@@ -32,6 +87,56 @@ struct Results {
     total_record_count: u32,
 }
 
+/// Maps the logical rider fields onto JSONPath expressions, so the tool can
+/// be pointed at result feeds whose records don't use the field names this
+/// file was originally written against. Any field left out of a supplied
+/// `--schema` file keeps its default path.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct Schema {
+    firstname: String,
+    lastname: String,
+    elapsedtime: String,
+    route: String,
+    bib: String,
+    id: String,
+}
+
+impl Default for Schema {
+    fn default() -> Schema {
+        Schema {
+            firstname: "$.firstname".to_string(),
+            lastname: "$.lastname".to_string(),
+            elapsedtime: "$.elapsedtime".to_string(),
+            route: "$.route".to_string(),
+            bib: "$.bib".to_string(),
+            id: "$._id".to_string(),
+        }
+    }
+}
+
+impl Schema {
+    fn from_path(path: &Path) -> std::result::Result<Schema, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let schema = if path.extension().and_then(|ext| ext.to_str()) == Some("toml") {
+            toml::from_str(&contents)?
+        } else {
+            serde_json::from_str(&contents)?
+        };
+        Ok(schema)
+    }
+}
+
+/// Evaluates `path` against `v`, returning its first match or `Value::Null`
+/// if the expression didn't select anything.
+fn select_first<'a>(v: &'a Value, path: &str) -> &'a Value {
+    const NULL: Value = Value::Null;
+    jsonpath_lib::select(v, path)
+        .ok()
+        .and_then(|matches| matches.into_iter().next())
+        .unwrap_or(&NULL)
+}
+
 #[derive(Debug, Clone)]
 struct Rider<'a> {
     firstname: &'a str,
@@ -47,7 +152,7 @@ struct Rider<'a> {
 }
 
 impl<'a> Rider<'a> {
-    fn from_value(v: &'a Value) -> Result<Rider<'a>> {
+    fn from_value(v: &'a Value, schema: &Schema) -> Result<Rider<'a>> {
         fn parse_duration(s: &str) -> Result<Duration> {
             let components: Vec<_> = s
                 .split(":")
@@ -107,34 +212,37 @@ impl<'a> Rider<'a> {
             Ok((course, wc, fr, gender))
         }
 
-        let firstname = v["firstname"].as_str().ok_or(de::Error::custom(format!(
-            "bad firstname {:?}",
-            v["firstname"]
-        )))?;
-        let lastname = v["lastname"].as_str().ok_or(de::Error::custom(format!(
-            "bad lastname {:?}",
-            v["lastname"]
-        )))?;
+        let firstname_value = select_first(v, &schema.firstname);
+        let firstname = firstname_value
+            .as_str()
+            .ok_or(de::Error::custom(format!("bad firstname {:?}", firstname_value)))?;
+        let lastname_value = select_first(v, &schema.lastname);
+        let lastname = lastname_value
+            .as_str()
+            .ok_or(de::Error::custom(format!("bad lastname {:?}", lastname_value)))?;
 
         if firstname.is_empty() && lastname.is_empty() {
             return Err(de::Error::custom("No riders with no name!"));
         }
 
-        let displaytime = v["elapsedtime"].as_str().ok_or(de::Error::custom(format!(
-            "bad time {:?}",
-            v["elapsedtime"]
-        )))?;
+        let displaytime_value = select_first(v, &schema.elapsedtime);
+        let displaytime = displaytime_value
+            .as_str()
+            .ok_or(de::Error::custom(format!("bad time {:?}", displaytime_value)))?;
         let elapsedtime = parse_duration(displaytime)?;
-        let route = v["route"]
+        let route_value = select_first(v, &schema.route);
+        let route = route_value
             .as_str()
-            .ok_or(de::Error::custom(format!("bad course {:?}", v["route"])))?;
+            .ok_or(de::Error::custom(format!("bad course {:?}", route_value)))?;
         let (course, willow_creek, fort_ross, gender) = parse_course(route)?;
-        let bib = v["bib"]
+        let bib_value = select_first(v, &schema.bib);
+        let bib = bib_value
             .as_u64()
-            .ok_or(de::Error::custom(format!("bad bibno {:?}", v["bib"])))?;
-        let _id = v["_id"]
+            .ok_or(de::Error::custom(format!("bad bibno {:?}", bib_value)))?;
+        let id_value = select_first(v, &schema.id);
+        let _id = id_value
             .as_str()
-            .ok_or(de::Error::custom(format!("bad id {:?}", v["_id"])))?;
+            .ok_or(de::Error::custom(format!("bad id {:?}", id_value)))?;
 
         Ok(Rider {
             firstname,
@@ -151,12 +259,90 @@ impl<'a> Rider<'a> {
     }
 }
 
+/// Computes the Levenshtein edit distance between two strings, the number of
+/// single-character insertions, deletions or substitutions needed to turn
+/// `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut prev_diag = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let above = row[j];
+            row[j] = (row[j] + 1).min(row[j - 1] + 1).min(prev_diag + cost);
+            prev_diag = above;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The number of typos tolerated for a query of the given length, modeled on
+/// MeiliSearch's length-scaled typo tolerance: short queries must match
+/// exactly, while longer ones allow for one or two slips.
+fn typo_budget(query_len: usize) -> usize {
+    match query_len {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+/// Matches `name` against `query`, returning the edit distance when it's
+/// within the typo budget for `query`'s length (or exactly 0 when `exact` is
+/// set, matching case-insensitively as before).
+fn match_name(name: &str, query: &str, exact: bool) -> Option<usize> {
+    if exact {
+        return if canonical_caseless_match_str(name, query) {
+            Some(0)
+        } else {
+            None
+        };
+    }
+
+    let distance = levenshtein(&name.to_lowercase(), &query.to_lowercase());
+    if distance <= typo_budget(query.chars().count()) {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// The course name plus its Willow Creek / Fort Ross variant suffixes, the
+/// same route description already appended to each rider's line.
+fn course_variant_label(r: &Rider) -> String {
+    format!(
+        "{}{}{}",
+        r.course,
+        if r.willow_creek { " +WC" } else { "" },
+        if r.fort_ross { " Fort Ross" } else { "" },
+    )
+}
+
+/// The category a rider's standing is reported within: their course variant
+/// plus gender, e.g. "Gran +WC Male".
+fn category_label(r: &Rider) -> String {
+    format!("{} {}", course_variant_label(r), r.gender)
+}
+
+/// A rider's rank within some group of `total` riders (1 = fastest),
+/// expressed as the percentage of the group they finished ahead of.
+fn percentile(rank: usize, total: usize) -> f64 {
+    (total - rank) as f64 / total as f64 * 100.0
+}
+
 struct FilterOptions<'a> {
     courses: Option<Vec<Course>>,
     gender: Option<Gender>,
     debug: bool,
     firstname: Option<&'a str>,
     lastname: Option<&'a str>,
+    exact: bool,
+    standings: bool,
 }
 
 impl<'a> FilterOptions<'a> {
@@ -166,6 +352,8 @@ impl<'a> FilterOptions<'a> {
         let debug = matches.is_present("debug");
         let firstname = matches.value_of("firstname");
         let lastname = matches.value_of("lastname");
+        let exact = matches.is_present("exact");
+        let standings = matches.is_present("standings");
 
         FilterOptions {
             courses,
@@ -173,6 +361,8 @@ impl<'a> FilterOptions<'a> {
             debug,
             firstname,
             lastname,
+            exact,
+            standings,
         }
     }
 }
@@ -182,11 +372,10 @@ struct Bikemonkey<'a> {
 }
 
 impl<'a> Bikemonkey<'a> {
-    fn from_json(blob: &'a Results, debug: bool) -> std::io::Result<Bikemonkey<'a>> {
-        let riders = blob
-            .records
+    fn from_json(records: &'a [Value], schema: &Schema, debug: bool) -> std::io::Result<Bikemonkey<'a>> {
+        let riders = records
             .iter()
-            .map(Rider::from_value)
+            .map(|v| Rider::from_value(v, schema))
             .filter_map(|r| {
                 if debug && r.is_err() {
                     println!("Warning: bad rider found {:?}", r);
@@ -221,55 +410,95 @@ impl<'a> Bikemonkey<'a> {
         riders
     }
 
+    /// Groups riders into their standings categories (see `category_label`),
+    /// preserving the ascending-elapsed-time order they were passed in so
+    /// each category's riders come out ranked.
+    fn standings_by_category<'b>(riders: &[&'b Rider<'a>]) -> Vec<(String, Vec<&'b Rider<'a>>)> {
+        let mut groups: Vec<(String, Vec<&'b Rider<'a>>)> = Vec::new();
+        for &r in riders {
+            let label = category_label(r);
+            match groups.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, group)) => group.push(r),
+                None => groups.push((label, vec![r])),
+            }
+        }
+        groups
+    }
+
     fn print_all(&self, filter_options: FilterOptions) {
         let riders = self.filter_riders(&filter_options);
+
+        if filter_options.standings {
+            let mut groups = Bikemonkey::standings_by_category(&riders);
+            groups.sort_by(|a, b| a.0.cmp(&b.0));
+            for (label, group) in groups {
+                println!("== {} ==", label);
+                for (idx, r) in group.iter().enumerate() {
+                    println!(
+                        "{} [{}, {}] {} {} ({})",
+                        idx + 1,
+                        r.bib,
+                        r._id,
+                        r.firstname,
+                        r.lastname,
+                        r.displaytime,
+                    );
+                }
+            }
+            return;
+        }
+
         for (idx, r) in riders.iter().enumerate() {
             println!(
-                "{} [{}, {}] {} {} ({}) {}{}{}",
+                "{} [{}, {}] {} {} ({}) {}",
                 idx + 1,
                 r.bib,
                 r._id,
                 r.firstname,
                 r.lastname,
                 r.displaytime,
-                r.course,
-                if r.willow_creek { " +WC" } else { "" },
-                if r.fort_ross { " Fort Ross" } else { "" },
+                course_variant_label(r),
             )
         }
     }
 
     fn print_info(&self, filter_options: FilterOptions) {
         let riders = self.filter_riders(&filter_options);
-        let matches = riders
+        let groups = Bikemonkey::standings_by_category(&riders);
+        let mut matches = riders
             .iter()
             .enumerate()
-            .filter(|&(_idx, r)| {
+            .filter_map(|(idx, r)| {
+                let mut distance = 0;
+
                 if let Some(ref name) = filter_options.firstname {
-                    if !canonical_caseless_match_str(&r.firstname, name) {
-                        return false;
+                    match match_name(&r.firstname, name, filter_options.exact) {
+                        Some(d) => distance += d,
+                        None => return None,
                     }
                 }
 
                 if let Some(ref name) = filter_options.lastname {
-                    if !canonical_caseless_match_str(&r.lastname, name) {
-                        return false;
+                    match match_name(&r.lastname, name, filter_options.exact) {
+                        Some(d) => distance += d,
+                        None => return None,
                     }
                 }
 
-                true
+                Some((idx, distance, r))
             })
             .collect::<Vec<_>>();
+        matches.sort_by_key(|&(_idx, distance, rider)| (distance, rider.elapsedtime));
 
         if matches.is_empty() {
             println!("No riders were found.");
             return;
         }
 
-        for &(idx, rider) in matches.iter() {
+        for &(idx, distance, rider) in matches.iter() {
             println!(
                 "Rider {} {} ({}) came in position {} with a time of {} out of {} matching rider{} on \
-                 the {}{}{} route",
+                 the {} route",
                 rider.firstname,
                 rider.lastname,
                 rider.bib,
@@ -277,10 +506,26 @@ impl<'a> Bikemonkey<'a> {
                 rider.displaytime,
                 riders.len(),
                 if riders.len() > 1 { "s" } else { "" },
-                rider.course,
-                if rider.willow_creek { " +WC" } else { "" },
-                if rider.fort_ross { " Fort Ross" } else { "" },
+                course_variant_label(rider),
             );
+
+            let label = category_label(rider);
+            if let Some((_, group)) = groups.iter().find(|(l, _)| *l == label) {
+                if let Some(cat_idx) = group.iter().position(|r| r._id == rider._id) {
+                    println!(
+                        "  {} of {} in category {} (faster than {:.0}% of finishers in {})",
+                        cat_idx + 1,
+                        group.len(),
+                        label,
+                        percentile(cat_idx + 1, group.len()),
+                        label,
+                    );
+                }
+            }
+
+            if filter_options.debug && !filter_options.exact {
+                println!("  (matched with edit distance {})", distance);
+            }
         }
     }
 }
@@ -321,7 +566,35 @@ fn main() {
                 .required(false),
         )
         .arg(Arg::from_usage("-d, --debug   'Enable debugging'"))
+        .arg(Arg::from_usage(
+            "--exact   'Require an exact (case-insensitive) name match instead of fuzzy matching'",
+        ))
+        .arg(
+            Arg::from_usage(
+                "--standings   'Group riders under category headers with intra-category ranks'",
+            )
+            .conflicts_with("firstname")
+            .conflicts_with("lastname"),
+        )
         .arg(Arg::from_usage("[file]        'File to read as input'"))
+        .arg(
+            Arg::with_name("url")
+                .short("u")
+                .long("url")
+                .multiple(false)
+                .help("URL of the race results service to fetch and page through instead of a file")
+                .takes_value(true)
+                .conflicts_with("file")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .multiple(false)
+                .help("TOML/JSON file mapping rider fields to JSONPath expressions")
+                .takes_value(true)
+                .required(false),
+        )
         .after_help(
             "Prints info about the riders in Levi's Gran Fondo. If \
              neither -f or -l are passed, prints all riders matching \
@@ -331,12 +604,22 @@ fn main() {
         .get_matches();
 
     let options = FilterOptions::from_arg_matches(&matches);
-    let path = Path::new(matches.value_of("file").unwrap_or("lgfresults.json"));
-    let file = File::open(&path).expect(&format!("couldn't open {}", path.display()));
-    let blob: Results =
-        serde_json::from_reader(file).expect(&format!("error parsing {}", path.display()));
-    let riders = match Bikemonkey::from_json(&blob, options.debug) {
-        Err(why) => panic!("couldn't open {}: {}", path.display(), why.description()),
+    let records = if let Some(url) = matches.value_of("url") {
+        fetch_all_records(url, options.debug).expect(&format!("error fetching results from {}", url))
+    } else {
+        let path = Path::new(matches.value_of("file").unwrap_or("lgfresults.json"));
+        let file = File::open(&path).expect(&format!("couldn't open {}", path.display()));
+        let blob: Results =
+            serde_json::from_reader(file).expect(&format!("error parsing {}", path.display()));
+        blob.records
+    };
+    let schema = match matches.value_of("schema") {
+        Some(path) => Schema::from_path(Path::new(path))
+            .expect(&format!("error reading schema {}", path)),
+        None => Schema::default(),
+    };
+    let riders = match Bikemonkey::from_json(&records, &schema, options.debug) {
+        Err(why) => panic!("couldn't load riders: {}", why.description()),
         Ok(riders) => riders,
     };
 